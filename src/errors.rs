@@ -0,0 +1,42 @@
+//! Error types returned by fallible operations on a `Handle`.
+
+use std::error::Error;
+use std::fmt;
+
+/// The error type returned by fallible operations on a `Handle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleErr {
+    /// The resource this handle referred to has already been dropped.
+    AlreadyDropped,
+    /// The resource this handle referred to is already borrowed, e.g. by a
+    /// `run`/`run_shared`/`run_upgradeable` call on another clone of the
+    /// same handle.
+    AlreadyBorrowed,
+    /// The resource this handle referred to was poisoned by a previously
+    /// panicking `run`/`run_poison`/`run_upgradeable` callback and hasn't
+    /// been recovered yet via `clear_poison`.
+    Poisoned
+}
+
+impl fmt::Display for HandleErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HandleErr::AlreadyDropped => write!(f, "tried to access an already dropped resource"),
+            HandleErr::AlreadyBorrowed => write!(f, "tried to access an already borrowed resource"),
+            HandleErr::Poisoned => write!(f, "tried to access a poisoned resource")
+        }
+    }
+}
+
+impl Error for HandleErr {
+    fn description(&self) -> &str {
+        match *self {
+            HandleErr::AlreadyDropped => "already dropped",
+            HandleErr::AlreadyBorrowed => "already borrowed",
+            HandleErr::Poisoned => "poisoned"
+        }
+    }
+}
+
+/// Shorthand for a `Result` whose error type is `HandleErr`.
+pub type HandleResult<T> = Result<T, HandleErr>;
@@ -0,0 +1,655 @@
+//! Generic machinery for safely handing out borrow-checked handles to
+//! wlroots resources that may be destroyed out from under us at any time.
+//!
+//! `TabletPad` used to hand-roll its own copy of the liveliness/lock-word/
+//! `Weak`/`upgrade`/`run`/`weak_reference` dance (see the git history of
+//! `types::input::tablet_pad` from before this module existed). This
+//! module centralizes that machinery behind a single audited `Handle<T>`,
+//! so future resource wrappers only have to implement `Handleable` to get
+//! `run`, `run_shared`, `try_run`, poisoning, and friends for free.
+use std::{fmt, ops::Deref, panic, ptr, rc::{Rc, Weak}, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+use errors::{HandleErr, HandleResult};
+
+use InputDevice;
+
+/// Sentinel stored in the lock word while a resource is held by an
+/// exclusive (writer) borrow.
+///
+/// Any other non-zero value is instead the number of outstanding shared
+/// (reader) borrows, following the same reader/writer discipline as
+/// `std::sync::RwLock` and spin's `RwLock`.
+pub(crate) const WRITER: usize = ::std::usize::MAX;
+
+/// Flag bit in the lock word marking that an upgradeable borrow is held.
+///
+/// Unlike `WRITER`, this is a single bit rather than a sentinel value, so it
+/// can be combined with an ordinary reader count in the remaining bits: an
+/// upgradeable borrow is allowed to coexist with plain `run_shared` readers,
+/// it just blocks the writer and any other upgradeable borrow.
+const UPGRADEABLE: usize = 1 << (8 * ::std::mem::size_of::<usize>() - 1);
+
+/// Which kind of borrow produced a `Handleable` resource from a `Handle`.
+///
+/// `Handleable::from_ptr` stashes this on the resource, and `run`/
+/// `run_shared`/`run_upgradeable` check in debug builds that it matches
+/// the borrow they took. Each of them releases its own kind of borrow
+/// (decrementing the reader count, clearing the writer sentinel, or
+/// clearing the upgradeable bit) without consulting this tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Borrow {
+    Read,
+    Write,
+    Upgradeable
+}
+
+/// Which kind of plain (non-upgradeable) borrow `Handle::upgrade` should
+/// take.
+///
+/// Kept separate from `Borrow` so that `upgrade`'s match is exhaustive by
+/// construction: `run_upgradeable` acquires the `UPGRADEABLE` bit itself
+/// and never goes through `upgrade`, so there is no third case for this
+/// function to handle.
+enum Acquire {
+    Read,
+    Write
+}
+
+/// The shared state backing a resource and all the `Handle`s pointing at it.
+///
+/// Bundled into a single `Rc` so the lock word and the poison flag always
+/// live and die together.
+#[derive(Debug)]
+pub(crate) struct Liveliness {
+    /// Reader/writer lock word, see the docs on `WRITER`.
+    pub(crate) lock: AtomicUsize,
+    /// Set when a `run`/`run_poison` callback panics while holding the
+    /// exclusive borrow, mirroring `std::sync::RwLock`'s poisoning, or when
+    /// a `run_upgradeable` callback panics (whether or not it upgraded).
+    pub(crate) poisoned: AtomicBool
+}
+
+impl Liveliness {
+    pub(crate) fn new() -> Self {
+        Liveliness { lock: AtomicUsize::new(0),
+                     poisoned: AtomicBool::new(false) }
+    }
+}
+
+/// Seals `Handleable` so that only resource types defined in this crate can
+/// implement it.
+///
+/// `Handleable` itself has to be `pub` since it's a bound on the public
+/// `Handle`/`Upgradable`, but nothing outside this crate should be able to
+/// hand `Handle<T>` a `T` whose liveliness/lock-word invariants we haven't
+/// audited.
+pub(crate) trait Sealed {}
+
+/// A resource type that can be safely wrapped behind a `Handle`.
+///
+/// Implementors provide the raw wlroots pointer type and how to construct
+/// themselves from one; `Handle<Self>` takes care of the liveliness
+/// tracking, borrow checking, and poisoning on top.
+pub trait Handleable: Sealed + Sized + fmt::Debug {
+    /// The raw `*mut wlr_*` type this resource wraps.
+    type Ptr: Copy;
+
+    /// Gets the liveliness `Rc`, if this is a canonical (not previously
+    /// upgraded) instance of the resource.
+    fn liveliness(&self) -> Option<&Rc<Liveliness>>;
+
+    /// Gets the raw wlroots pointer backing this resource.
+    fn as_ptr(&self) -> Self::Ptr;
+
+    /// Gets the `wlr_input_device` associated with this resource.
+    fn input_device(&self) -> &InputDevice;
+
+    /// Gets the kind of borrow that produced this resource via `from_ptr`,
+    /// or `None` if this is a canonical (not previously upgraded) instance.
+    fn borrow_kind(&self) -> Option<Borrow>;
+
+    /// Builds the resource from a `Handle`'s raw pointer and device, tagging
+    /// it with the kind of borrow that produced it.
+    ///
+    /// # Safety
+    /// `ptr` must point to a live, correctly typed wlroots resource.
+    unsafe fn from_ptr(ptr: Self::Ptr, device: InputDevice, borrow: Borrow) -> Self;
+}
+
+/// Manually sets the lock used to determine if a double-borrow is occuring
+/// on `resource`.
+///
+/// # Panics
+/// Panics when trying to set the lock on a previously upgraded resource.
+pub(crate) unsafe fn set_lock<T: Handleable>(resource: &T, val: bool) {
+    resource.liveliness()
+            .expect("Tried to set lock on borrowed resource")
+            .lock
+            .store(if val { WRITER } else { 0 }, Ordering::Release)
+}
+
+/// A handle to a `T`, valid only as long as the underlying wlroots resource
+/// is alive.
+///
+/// Replaces the hand-rolled liveliness/lock-word machinery that every
+/// input and output wrapper in this crate used to duplicate.
+#[derive(Debug)]
+pub struct Handle<T: Handleable> {
+    handle: Weak<Liveliness>,
+    device: InputDevice,
+    ptr: T::Ptr
+}
+
+impl<T: Handleable> Handle<T> {
+    /// Constructs a new `Handle` that is always invalid. Calling `run` on
+    /// this will always fail.
+    ///
+    /// This is useful for pre-filling a value before it's provided by the
+    /// server, or for mocking/testing.
+    pub fn new() -> Self
+        where T::Ptr: Default
+    {
+        unsafe {
+            Handle { handle: Weak::new(),
+                     // NOTE Rationale for null pointer here:
+                     // It's never used, because you can never upgrade it,
+                     // so no way to dereference it and trigger UB.
+                     device: InputDevice::from_ptr(ptr::null_mut()),
+                     ptr: T::Ptr::default() }
+        }
+    }
+
+    /// Creates a `Handle` that refers back to `resource`, the canonical
+    /// (not previously upgraded) instance of a `T`.
+    ///
+    /// # Panics
+    /// If `resource` is itself a previously upgraded value, then this
+    /// function will panic.
+    pub(crate) fn from_resource(resource: &T) -> Self {
+        let liveliness = resource.liveliness()
+                                  .expect("Cannot downgrade previously upgraded handle!");
+        Handle { handle: Rc::downgrade(liveliness),
+                 // NOTE Rationale for cloning:
+                 // We can't use the handle unless the resource is alive,
+                 // which means the device pointer is still alive.
+                 device: unsafe { resource.input_device().clone() },
+                 ptr: resource.as_ptr() }
+    }
+
+    /// Upgrades the handle to a reference to the backing resource, taking
+    /// either a shared (read) or exclusive (write) borrow of the lock word
+    /// depending on `kind`.
+    ///
+    /// Unless `ignore_poison` is set, a handle that was poisoned by a
+    /// previously panicking `run` callback fails with
+    /// `HandleErr::Poisoned` instead of being upgraded.
+    ///
+    /// # Unsafety
+    /// This function is unsafe, because it creates an unbounded resource
+    /// which may live forever..
+    /// But no wlroots resource lives forever and might be destroyed at any
+    /// time.
+    unsafe fn upgrade(&self, kind: Acquire, ignore_poison: bool) -> HandleResult<T> {
+        self.handle.upgrade()
+            .ok_or(HandleErr::AlreadyDropped)
+            // NOTE
+            // We drop the Rc here because having two would allow a dangling
+            // pointer to exist!
+            .and_then(|liveliness| {
+                if !ignore_poison && liveliness.poisoned.load(Ordering::Acquire) {
+                    return Err(HandleErr::Poisoned)
+                }
+                match kind {
+                    Acquire::Write => {
+                        liveliness.lock
+                                  .compare_exchange(0, WRITER, Ordering::AcqRel, Ordering::Acquire)
+                                  .map_err(|_| HandleErr::AlreadyBorrowed)?;
+                    }
+                    Acquire::Read => {
+                        loop {
+                            let cur = liveliness.lock.load(Ordering::Acquire);
+                            if cur == WRITER {
+                                return Err(HandleErr::AlreadyBorrowed)
+                            }
+                            if liveliness.lock
+                                         .compare_exchange_weak(cur,
+                                                                 cur + 1,
+                                                                 Ordering::AcqRel,
+                                                                 Ordering::Acquire)
+                                         .is_ok()
+                            {
+                                break
+                            }
+                        }
+                    }
+                }
+                let borrow = match kind {
+                    Acquire::Write => Borrow::Write,
+                    Acquire::Read => Borrow::Read
+                };
+                Ok(T::from_ptr(self.ptr, self.device.clone(), borrow))
+            })
+    }
+
+    /// Run a function on the referenced resource, if it still exists.
+    ///
+    /// Returns the result of the function, if successful.
+    ///
+    /// # Safety
+    /// By enforcing a rather harsh limit on the lifetime of the output
+    /// to a short lived scope of an anonymous function,
+    /// this function ensures the resource does not live longer than it
+    /// exists.
+    ///
+    /// If the resource is already borrowed (e.g. this is called within
+    /// another `run` to the same resource, through any clone of the
+    /// handle), this returns `Err(HandleErr::AlreadyBorrowed)` without
+    /// calling `runner`.
+    ///
+    /// # Panics
+    /// This function will panic if the exclusive lock is found not to be
+    /// held once `runner` returns, and resumes any panic raised by `runner`.
+    ///
+    /// If `runner` panics, the handle is poisoned: subsequent `run`/`run_shared`
+    /// calls will fail with `HandleErr::Poisoned` until `clear_poison` or
+    /// `run_poison` is used to deliberately recover the resource.
+    pub fn run<F, R>(&mut self, runner: F) -> HandleResult<R>
+        where F: FnOnce(&mut T) -> R
+    {
+        self.run_mut(runner, false)
+    }
+
+    /// Like `run`, but proceeds even if the handle has been poisoned by a
+    /// previously panicking `run` callback, handing the caller the
+    /// resource to inspect or repair.
+    ///
+    /// Mirrors `PoisonError::into_inner`: this does not itself clear the
+    /// poison flag, use `clear_poison` once you're satisfied the resource
+    /// is in a sane state again.
+    pub fn run_poison<F, R>(&mut self, runner: F) -> HandleResult<R>
+        where F: FnOnce(&mut T) -> R
+    {
+        self.run_mut(runner, true)
+    }
+
+    fn run_mut<F, R>(&mut self, runner: F, ignore_poison: bool) -> HandleResult<R>
+        where F: FnOnce(&mut T) -> R
+    {
+        let mut resource = unsafe { self.upgrade(Acquire::Write, ignore_poison)? };
+        debug_assert_eq!(resource.borrow_kind(), Some(Borrow::Write));
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| runner(&mut resource)));
+        self.handle.upgrade().map(|liveliness| {
+                                      // Sanity check that it hasn't been tampered with.
+                                      if liveliness.lock.load(Ordering::Acquire) != WRITER {
+                                          wlr_log!(L_ERROR,
+                                                   "After running callback, mutable lock was \
+                                                    not held for: {:?}",
+                                                   resource);
+                                          panic!("Lock in incorrect state!");
+                                      }
+                                      liveliness.lock.store(0, Ordering::Release);
+                                      if res.is_err() {
+                                          liveliness.poisoned.store(true, Ordering::Release);
+                                      }
+                                  });
+        match res {
+            Ok(res) => Ok(res),
+            Err(err) => panic::resume_unwind(err)
+        }
+    }
+
+    /// Like `run`, but treats an already-borrowed handle as a recoverable
+    /// condition instead of a hard error: returns `Ok(None)` rather than
+    /// `Err(HandleErr::AlreadyBorrowed)` when the borrow is contended.
+    ///
+    /// Useful in event-loop code that would rather skip a resource that is
+    /// currently in use than abort the whole frame.
+    pub fn try_run<F, R>(&mut self, runner: F) -> HandleResult<Option<R>>
+        where F: FnOnce(&mut T) -> R
+    {
+        match self.run(runner) {
+            Ok(res) => Ok(Some(res)),
+            Err(HandleErr::AlreadyBorrowed) => Ok(None),
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Run a function on the referenced resource with a shared, read-only
+    /// borrow, if it still exists.
+    ///
+    /// Unlike `run`, this may be called concurrently with other
+    /// `run_shared` calls on clones of the same handle, since none of them
+    /// are allowed to mutate the underlying resource.
+    ///
+    /// Returns the result of the function, if successful.
+    ///
+    /// If a mutable borrow is currently held, e.g. from a `run` call to the
+    /// same resource (directly, or through another clone of the handle),
+    /// this returns `Err(HandleErr::AlreadyBorrowed)` without calling
+    /// `runner`.
+    ///
+    /// Unlike `run`/`run_poison`, a panicking callback here does *not*
+    /// poison the handle (just as `std::sync::RwLock` is not poisoned by a
+    /// panicking read guard) - a read-only callback can't have left the
+    /// resource itself in an inconsistent state, so there's nothing to
+    /// protect later callers from.
+    pub fn run_shared<F, R>(&self, runner: F) -> HandleResult<R>
+        where F: FnOnce(&T) -> R
+    {
+        let resource = unsafe { self.upgrade(Acquire::Read, false)? };
+        debug_assert_eq!(resource.borrow_kind(), Some(Borrow::Read));
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| runner(&resource)));
+        self.handle.upgrade().map(|liveliness| {
+                                      // Sanity check that it hasn't been tampered with.
+                                      let previous = liveliness.lock.fetch_sub(1, Ordering::Release);
+                                      if previous == 0 || previous == WRITER {
+                                          wlr_log!(L_ERROR,
+                                                   "After running callback, shared lock was not \
+                                                    held for: {:?}",
+                                                   resource);
+                                          panic!("Lock in incorrect state!");
+                                      }
+                                  });
+        match res {
+            Ok(res) => Ok(res),
+            Err(err) => panic::resume_unwind(err)
+        }
+    }
+
+    /// Run a function on the referenced resource with an upgradeable borrow,
+    /// if it still exists.
+    ///
+    /// An upgradeable borrow behaves like a `run_shared` borrow (it may
+    /// coexist with ordinary shared readers, and derefs to `&T`) except that
+    /// only one may be outstanding at a time, and it blocks the writer and
+    /// any other upgradeable borrow. The callback can call
+    /// `Upgradable::upgrade` to promote it to an exclusive borrow once all
+    /// plain readers have drained, closing the read-then-write race window
+    /// that releasing and re-acquiring via separate `run_shared`/`run` calls
+    /// would open.
+    ///
+    /// Returns the result of the function, if successful.
+    ///
+    /// If a writer or another upgradeable borrow is currently held, this
+    /// returns `Err(HandleErr::AlreadyBorrowed)` without calling `runner`.
+    pub fn run_upgradeable<F, R>(&self, runner: F) -> HandleResult<R>
+        where F: for<'a> FnOnce(Upgradable<'a, T>) -> R
+    {
+        let liveliness = self.handle.upgrade().ok_or(HandleErr::AlreadyDropped)?;
+        if liveliness.poisoned.load(Ordering::Acquire) {
+            return Err(HandleErr::Poisoned)
+        }
+        loop {
+            let cur = liveliness.lock.load(Ordering::Acquire);
+            if cur == WRITER || cur & UPGRADEABLE != 0 {
+                return Err(HandleErr::AlreadyBorrowed)
+            }
+            if liveliness.lock
+                         .compare_exchange_weak(cur, cur | UPGRADEABLE, Ordering::AcqRel, Ordering::Acquire)
+                         .is_ok()
+            {
+                break
+            }
+        }
+        let mut resource = unsafe { T::from_ptr(self.ptr, self.device.clone(), Borrow::Upgradeable) };
+        debug_assert_eq!(resource.borrow_kind(), Some(Borrow::Upgradeable));
+        let view = Upgradable { resource: &mut resource,
+                                liveliness: &liveliness };
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| runner(view)));
+        self.handle.upgrade().map(|liveliness| {
+                                      // `upgrade` flips the word all the way to `WRITER`; otherwise
+                                      // just clear our upgradeable bit, leaving any plain readers be.
+                                      if liveliness.lock.load(Ordering::Acquire) == WRITER {
+                                          liveliness.lock.store(0, Ordering::Release);
+                                      } else {
+                                          liveliness.lock.fetch_and(!UPGRADEABLE, Ordering::Release);
+                                      }
+                                      if res.is_err() {
+                                          liveliness.poisoned.store(true, Ordering::Release);
+                                      }
+                                  });
+        match res {
+            Ok(res) => Ok(res),
+            Err(err) => panic::resume_unwind(err)
+        }
+    }
+
+    /// Returns `true` if this handle's resource is currently borrowed:
+    /// mutably (via `run`/`run_poison`), by one or more `run_shared` calls,
+    /// or by a `run_upgradeable` call - the last of which sets this even
+    /// with zero concurrent readers, since the lock word's `UPGRADEABLE`
+    /// bit alone is enough to make this `true`.
+    pub fn is_borrowed(&self) -> bool {
+        match self.handle.upgrade() {
+            Some(liveliness) => liveliness.lock.load(Ordering::Acquire) != 0,
+            None => false
+        }
+    }
+
+    /// Returns `true` if a previous `run`/`run_poison`/`run_upgradeable`
+    /// callback panicked, poisoning the underlying resource.
+    pub fn is_poisoned(&self) -> bool {
+        match self.handle.upgrade() {
+            Some(liveliness) => liveliness.poisoned.load(Ordering::Acquire),
+            None => false
+        }
+    }
+
+    /// Clears the poison flag set by a previously panicking `run` callback,
+    /// allowing future `run`/`run_shared` calls to proceed as normal.
+    ///
+    /// # Safety
+    /// The caller must be sure the underlying wlroots resource was not left
+    /// in an inconsistent state by whatever panic poisoned it.
+    pub unsafe fn clear_poison(&self) {
+        if let Some(liveliness) = self.handle.upgrade() {
+            liveliness.poisoned.store(false, Ordering::Release);
+        }
+    }
+
+    /// Gets the `wlr_input_device` associated with this handle.
+    pub fn input_device(&self) -> HandleResult<&InputDevice> {
+        match self.handle.upgrade() {
+            Some(_) => Ok(&self.device),
+            None => Err(HandleErr::AlreadyDropped)
+        }
+    }
+
+    /// Gets the raw wlroots pointer associated with this handle.
+    pub(crate) unsafe fn as_ptr(&self) -> T::Ptr {
+        self.ptr
+    }
+}
+
+impl<T: Handleable> Default for Handle<T> where T::Ptr: Default {
+    fn default() -> Self {
+        Handle::new()
+    }
+}
+
+impl<T: Handleable> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle { ptr: self.ptr,
+                 handle: self.handle.clone(),
+                 // NOTE Rationale for unsafe clone:
+                 //
+                 // You can only access it after a call to `upgrade`,
+                 // and that implicitly checks that it is valid.
+                 device: unsafe { self.device.clone() } }
+    }
+}
+
+impl<T: Handleable> PartialEq for Handle<T> where T::Ptr: PartialEq {
+    fn eq(&self, other: &Handle<T>) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T: Handleable> Eq for Handle<T> where T::Ptr: PartialEq {}
+
+/// An upgradeable, shared view of a resource, handed to the callback passed
+/// to `Handle::run_upgradeable`.
+///
+/// Derefs to `&T` like a `run_shared` borrow, but can additionally be
+/// promoted to an exclusive `&mut T` via `upgrade`.
+pub struct Upgradable<'a, T: Handleable + 'a> {
+    resource: &'a mut T,
+    liveliness: &'a Rc<Liveliness>
+}
+
+impl<'a, T: Handleable + 'a> Deref for Upgradable<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.resource
+    }
+}
+
+impl<'a, T: Handleable + 'a> Upgradable<'a, T> {
+    /// Promotes this upgradeable borrow to an exclusive one, spinning until
+    /// any outstanding `run_shared` readers have drained.
+    pub fn upgrade(self) -> &'a mut T {
+        loop {
+            let cur = self.liveliness.lock.load(Ordering::Acquire);
+            debug_assert!(cur & UPGRADEABLE != 0, "upgradeable bit was cleared from under us");
+            if cur == UPGRADEABLE {
+                if self.liveliness
+                       .lock
+                       .compare_exchange_weak(cur, WRITER, Ordering::AcqRel, Ordering::Acquire)
+                       .is_ok()
+                {
+                    break
+                }
+            }
+        }
+        self.resource
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal `Handleable` standing in for a real wlroots resource, so
+    /// the borrow/poison/upgrade machinery can be exercised without a live
+    /// `wlr_tablet_pad`. Mirrors `TabletPad`'s shape exactly, just with a
+    /// dummy pointer instead of a real wlroots one.
+    #[derive(Debug)]
+    struct MockResource {
+        liveliness: Option<Rc<Liveliness>>,
+        borrow: Option<Borrow>,
+        device: InputDevice,
+        ptr: *mut u32
+    }
+
+    impl Sealed for MockResource {}
+
+    impl Handleable for MockResource {
+        type Ptr = *mut u32;
+
+        fn liveliness(&self) -> Option<&Rc<Liveliness>> {
+            self.liveliness.as_ref()
+        }
+
+        fn as_ptr(&self) -> Self::Ptr {
+            self.ptr
+        }
+
+        fn input_device(&self) -> &InputDevice {
+            &self.device
+        }
+
+        fn borrow_kind(&self) -> Option<Borrow> {
+            self.borrow
+        }
+
+        unsafe fn from_ptr(ptr: Self::Ptr, device: InputDevice, borrow: Borrow) -> Self {
+            MockResource { liveliness: None,
+                            borrow: Some(borrow),
+                            device,
+                            ptr }
+        }
+    }
+
+    fn mock_resource() -> MockResource {
+        MockResource { liveliness: Some(Rc::new(Liveliness::new())),
+                        borrow: None,
+                        // NOTE Never dereferenced, same rationale as `Handle::new`.
+                        device: unsafe { InputDevice::from_ptr(ptr::null_mut()) },
+                        ptr: ptr::null_mut() }
+    }
+
+    fn assert_already_borrowed<R>(result: HandleResult<R>) {
+        match result {
+            Err(HandleErr::AlreadyBorrowed) => (),
+            Err(HandleErr::Poisoned) => panic!("expected AlreadyBorrowed, got Poisoned"),
+            Err(HandleErr::AlreadyDropped) => panic!("expected AlreadyBorrowed, got AlreadyDropped"),
+            Ok(_) => panic!("expected AlreadyBorrowed, got Ok")
+        }
+    }
+
+    #[test]
+    fn double_write_borrow_is_rejected() {
+        let resource = mock_resource();
+        let mut handle = Handle::from_resource(&resource);
+        let mut other = handle.clone();
+        let result = handle.run(|_| other.run(|_| ()));
+        assert_already_borrowed(result.expect("outer run should have succeeded"));
+    }
+
+    #[test]
+    fn writer_is_rejected_while_reader_is_borrowed() {
+        let resource = mock_resource();
+        let handle = Handle::from_resource(&resource);
+        let mut writer = handle.clone();
+        let result = handle.run_shared(|_| writer.run(|_| ()));
+        assert_already_borrowed(result.expect("run_shared should have succeeded"));
+    }
+
+    #[test]
+    fn reader_is_rejected_while_writer_is_borrowed() {
+        let resource = mock_resource();
+        let mut handle = Handle::from_resource(&resource);
+        let reader = handle.clone();
+        let result = handle.run(|_| reader.run_shared(|_| ()));
+        assert_already_borrowed(result.expect("run should have succeeded"));
+    }
+
+    #[test]
+    fn panicking_run_poisons_until_cleared() {
+        let resource = mock_resource();
+        let mut handle = Handle::from_resource(&resource);
+
+        assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| handle.run(|_| panic!("boom")))).is_err());
+        assert!(handle.is_poisoned());
+
+        match handle.run(|_| ()) {
+            Err(HandleErr::Poisoned) => (),
+            Ok(_) => panic!("expected Poisoned, got Ok"),
+            Err(_) => panic!("expected Poisoned, got a different error")
+        }
+
+        handle.run_poison(|_| ()).expect("run_poison should proceed despite the poison flag");
+        unsafe { handle.clear_poison() };
+        assert!(!handle.is_poisoned());
+        handle.run(|_| ()).expect("handle should be usable again after clear_poison");
+    }
+
+    #[test]
+    fn upgradeable_allows_readers_until_upgraded() {
+        let resource = mock_resource();
+        let handle = Handle::from_resource(&resource);
+        let reader = handle.clone();
+        let mut writer = handle.clone();
+
+        handle.run_upgradeable(|view| {
+                  reader.run_shared(|_| ())
+                        .expect("a plain reader may coexist with an upgradeable borrow");
+                  let upgraded = view.upgrade();
+                  assert_already_borrowed(reader.run_shared(|_| ()));
+                  assert_already_borrowed(writer.run(|_| ()));
+                  let _ = upgraded;
+              })
+              .expect("run_upgradeable should have succeeded");
+    }
+}
\ No newline at end of file
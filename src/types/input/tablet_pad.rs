@@ -1,9 +1,9 @@
 //! TODO Documentation
-use std::{panic, ptr, rc::{Rc, Weak}, sync::atomic::{AtomicBool, Ordering}};
+use std::rc::Rc;
 
-use errors::{HandleErr, HandleResult};
 use wlroots_sys::{wlr_input_device, wlr_tablet_pad};
 
+use types::handle::{self, Borrow, Handle, Handleable, Liveliness, Upgradable};
 use InputDevice;
 
 #[derive(Debug)]
@@ -17,23 +17,52 @@ pub struct TabletPad {
     /// the operations are **unchecked**.
     /// This is means safe operations might fail, but only if you use the unsafe
     /// marked function `upgrade` on a `TabletPadHandle`.
-    liveliness: Option<Rc<AtomicBool>>,
+    liveliness: Option<Rc<Liveliness>>,
+    /// Which kind of borrow this `TabletPad` was upgraded as, reported by
+    /// `Handleable::borrow_kind`. Only meaningful when `liveliness` is
+    /// `None` (i.e. this came from an upgraded `TabletPadHandle`).
+    borrow: Option<Borrow>,
     /// The device that refers to this tablet pad.
     device: InputDevice,
     /// Underlying tablet state
     pad: *mut wlr_tablet_pad
 }
 
-#[derive(Debug)]
-pub struct TabletPadHandle {
-    /// The Rc that ensures that this handle is still alive.
-    ///
-    /// When wlroots deallocates the tablet tool associated with this handle,
-    handle: Weak<AtomicBool>,
-    /// The device that refers to this tablet_pad.
-    device: InputDevice,
-    /// The underlying tablet state
-    pad: *mut wlr_tablet_pad
+/// A handle to a `TabletPad`, valid only as long as the underlying
+/// `wlr_tablet_pad` is alive.
+pub type TabletPadHandle = Handle<TabletPad>;
+
+/// An upgradeable, shared view of a `TabletPad`, handed to the callback
+/// passed to `TabletPadHandle::run_upgradeable`.
+pub type UpgradableTabletPad<'a> = Upgradable<'a, TabletPad>;
+
+impl handle::Sealed for TabletPad {}
+
+impl Handleable for TabletPad {
+    type Ptr = *mut wlr_tablet_pad;
+
+    fn liveliness(&self) -> Option<&Rc<Liveliness>> {
+        self.liveliness.as_ref()
+    }
+
+    fn as_ptr(&self) -> Self::Ptr {
+        self.pad
+    }
+
+    fn input_device(&self) -> &InputDevice {
+        &self.device
+    }
+
+    fn borrow_kind(&self) -> Option<Borrow> {
+        self.borrow
+    }
+
+    unsafe fn from_ptr(pad: Self::Ptr, device: InputDevice, borrow: Borrow) -> Self {
+        TabletPad { liveliness: None,
+                    borrow: Some(borrow),
+                    device,
+                    pad }
+    }
 }
 
 impl TabletPad {
@@ -49,7 +78,8 @@ impl TabletPad {
         match (*device).type_ {
             WLR_INPUT_DEVICE_TABLET_PAD => {
                 let pad = (*device).__bindgen_anon_1.tablet_pad;
-                Some(TabletPad { liveliness: Some(Rc::new(AtomicBool::new(false))),
+                Some(TabletPad { liveliness: Some(Rc::new(Liveliness::new())),
+                                 borrow: None,
                                  device: InputDevice::from_ptr(device),
                                  pad })
             }
@@ -57,12 +87,6 @@ impl TabletPad {
         }
     }
 
-    unsafe fn from_handle(handle: &TabletPadHandle) -> HandleResult<Self> {
-        Ok(TabletPad { liveliness: None,
-                       device: handle.input_device()?.clone(),
-                       pad: handle.as_ptr() })
-    }
-
     /// Gets the wlr_input_device associated with this TabletPad.
     pub fn input_device(&self) -> &InputDevice {
         &self.device
@@ -76,14 +100,7 @@ impl TabletPad {
     /// If this `TabletPad` is a previously upgraded `TabletPad`,
     /// then this function will panic.
     pub fn weak_reference(&self) -> TabletPadHandle {
-        let arc = self.liveliness.as_ref()
-                      .expect("Cannot downgrade previously upgraded TabletPadHandle!");
-        TabletPadHandle { handle: Rc::downgrade(arc),
-                          // NOTE Rationale for cloning:
-                          // We can't use the tablet tool handle unless the tablet tool is alive,
-                          // which means the device pointer is still alive.
-                          device: unsafe { self.device.clone() },
-                          pad: self.pad }
+        Handle::from_resource(self)
     }
 
     /// Manually set the lock used to determine if a double-borrow is
@@ -92,9 +109,7 @@ impl TabletPad {
     /// # Panics
     /// Panics when trying to set the lock on an upgraded handle.
     pub(crate) unsafe fn set_lock(&self, val: bool) {
-        self.liveliness.as_ref()
-            .expect("Tried to set lock on borrowed TabletPad")
-            .store(val, Ordering::Release)
+        handle::set_lock(self, val)
     }
 }
 
@@ -115,120 +130,3 @@ impl Drop for TabletPad {
         }
     }
 }
-
-impl TabletPadHandle {
-    /// Constructs a new TabletPadHandle that is always invalid. Calling `run` on this
-    /// will always fail.
-    ///
-    /// This is useful for pre-filling a value before it's provided by the server, or
-    /// for mocking/testing.
-    pub fn new() -> Self {
-        unsafe {
-            TabletPadHandle { handle: Weak::new(),
-                              // NOTE Rationale for null pointer here:
-                              // It's never used, because you can never upgrade it,
-                              // so no way to dereference it and trigger UB.
-                              device: InputDevice::from_ptr(ptr::null_mut()),
-                              pad: ptr::null_mut() }
-        }
-    }
-
-    /// Upgrades the tablet tool handle to a reference to the backing `TabletPad`.
-    ///
-    /// # Unsafety
-    /// This function is unsafe, because it creates an unbounded `TabletPad`
-    /// which may live forever..
-    /// But no tablet tool lives forever and might be disconnected at any time.
-    pub(crate) unsafe fn upgrade(&self) -> HandleResult<TabletPad> {
-        self.handle.upgrade()
-            .ok_or(HandleErr::AlreadyDropped)
-            // NOTE
-            // We drop the Rc here because having two would allow a dangling
-            // pointer to exist!
-            .and_then(|check| {
-                let pad = TabletPad::from_handle(self)?;
-                if check.load(Ordering::Acquire) {
-                    return Err(HandleErr::AlreadyBorrowed)
-                }
-                check.store(true, Ordering::Release);
-                Ok(pad)
-            })
-    }
-
-    /// Run a function on the referenced TabletPad, if it still exists
-    ///
-    /// Returns the result of the function, if successful
-    ///
-    /// # Safety
-    /// By enforcing a rather harsh limit on the lifetime of the output
-    /// to a short lived scope of an anonymous function,
-    /// this function ensures the TabletPad does not live longer
-    /// than it exists.
-    ///
-    /// # Panics
-    /// This function will panic if multiple mutable borrows are detected.
-    /// This will happen if you call `upgrade` directly within this callback,
-    /// or if you run this function within the another run to the same `TabletPad`.
-    ///
-    /// So don't nest `run` calls and everything will be ok :).
-    pub fn run<F, R>(&mut self, runner: F) -> HandleResult<R>
-        where F: FnOnce(&mut TabletPad) -> R
-    {
-        let mut pad = unsafe { self.upgrade()? };
-        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| runner(&mut pad)));
-        self.handle.upgrade().map(|check| {
-                                      // Sanity check that it hasn't been tampered with.
-                                      if !check.load(Ordering::Acquire) {
-                                          wlr_log!(L_ERROR,
-                                                   "After running tablet tool callback, mutable \
-                                                    lock was false for: {:?}",
-                                                   pad);
-                                          panic!("Lock in incorrect state!");
-                                      }
-                                      check.store(false, Ordering::Release);
-                                  });
-        match res {
-            Ok(res) => Ok(res),
-            Err(err) => panic::resume_unwind(err)
-        }
-    }
-
-    /// Gets the wlr_input_device associated with this TabletPadHandle
-    pub fn input_device(&self) -> HandleResult<&InputDevice> {
-        match self.handle.upgrade() {
-            Some(_) => Ok(&self.device),
-            None => Err(HandleErr::AlreadyDropped)
-        }
-    }
-
-    /// Gets the wlr_tablet_tool associated with this TabletPadHandle.
-    pub(crate) unsafe fn as_ptr(&self) -> *mut wlr_tablet_pad {
-        self.pad
-    }
-}
-
-impl Default for TabletPadHandle {
-    fn default() -> Self {
-        TabletPadHandle::new()
-    }
-}
-
-impl Clone for TabletPadHandle {
-    fn clone(&self) -> Self {
-        TabletPadHandle { pad: self.pad,
-                          handle: self.handle.clone(),
-                          /// NOTE Rationale for unsafe clone:
-                          ///
-                          /// You can only access it after a call to `upgrade`,
-                          /// and that implicitly checks that it is valid.
-                          device: unsafe { self.device.clone() } }
-    }
-}
-
-impl PartialEq for TabletPadHandle {
-    fn eq(&self, other: &TabletPadHandle) -> bool {
-        self.pad == other.pad
-    }
-}
-
-impl Eq for TabletPadHandle {}